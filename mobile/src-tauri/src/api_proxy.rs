@@ -1,7 +1,53 @@
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
-use tauri::{command, AppHandle, Emitter};
+use std::sync::Mutex;
+use std::path::{Path, PathBuf};
+use tauri::{command, AppHandle, Emitter, Manager};
 use futures_util::StreamExt;
+use eventsource_stream::Eventsource;
+use once_cell::sync::Lazy;
+use tokio_util::sync::CancellationToken;
+use base64::Engine;
+use rand::Rng;
+use tokio::io::AsyncWriteExt;
+
+/// 正在进行的流式请求的取消句柄，key 为 `stream_id`。
+/// 在 `api_stream_proxy` 发起请求时注册，在流结束/出错/被取消时移除。
+static STREAM_REGISTRY: Lazy<Mutex<HashMap<String, CancellationToken>>> =
+    Lazy::new(|| Mutex::new(HashMap::new()));
+
+fn unregister_stream(stream_id: &str) {
+    STREAM_REGISTRY.lock().unwrap().remove(stream_id);
+}
+
+/// 根据 HTTP 方法名在 `client` 上选出对应的请求构造器；被 `api_proxy`、
+/// `api_stream_proxy` 和 `bklite://` 协议处理器共用，保证三者的方法支持面一致
+pub(crate) fn method_request_builder(
+    client: &reqwest::Client,
+    method: &str,
+    url: &str,
+) -> Result<reqwest::RequestBuilder, String> {
+    match method.to_uppercase().as_str() {
+        "GET" => Ok(client.get(url)),
+        "POST" => Ok(client.post(url)),
+        "PUT" => Ok(client.put(url)),
+        "DELETE" => Ok(client.delete(url)),
+        "PATCH" => Ok(client.patch(url)),
+        "HEAD" => Ok(client.head(url)),
+        "OPTIONS" => Ok(client.request(reqwest::Method::OPTIONS, url)),
+        other => Err(format!("Unsupported HTTP method: {}", other)),
+    }
+}
+
+/// `ClientBuilder::gzip`/`brotli`/`deflate` wrap the underlying hyper body with a
+/// decompression layer before any user code sees it, so `bytes_stream()` already
+/// yields plaintext and `Content-Encoding`/`Content-Length` are already stripped by
+/// reqwest itself. No manual decoding is needed here; this just removes the now-stale
+/// header for callers who still see it (some servers send Content-Encoding even on a
+/// response reqwest has already decompressed).
+pub(crate) fn strip_content_encoding(headers: &mut HashMap<String, String>) {
+    headers.retain(|k, _| !k.eq_ignore_ascii_case("content-encoding"));
+}
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct ApiRequest {
@@ -9,11 +55,204 @@ pub struct ApiRequest {
     pub method: String,
     pub headers: Option<HashMap<String, String>>,
     pub body: Option<String>,
+    /// 响应体的读取方式，默认为 `Text`（保持旧行为）
+    #[serde(default)]
+    pub response_mode: ResponseMode,
+    /// 整个请求的超时时间（毫秒），不设置则使用 reqwest 默认（不超时）
+    pub timeout_ms: Option<u64>,
+    /// 建立连接阶段的超时时间（毫秒）
+    pub connect_timeout_ms: Option<u64>,
+    /// 允许的最大重定向跳转次数，`Some(0)` 表示禁止重定向
+    pub max_redirects: Option<usize>,
+    /// 失败重试策略，不设置则不重试（`max_attempts: 1`）
+    pub retry: Option<RetryConfig>,
+}
+
+/// 非流式请求的重试策略：指数退避 + 抖动，遇到 `retryable_statuses` 中的状态码
+/// 或连接/超时错误时重试。流式请求只会在拿到首个事件之前按同样的策略重试。
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct RetryConfig {
+    #[serde(default = "RetryConfig::default_max_attempts")]
+    pub max_attempts: u32,
+    #[serde(default = "RetryConfig::default_base_delay_ms")]
+    pub base_delay_ms: u64,
+    #[serde(default = "RetryConfig::default_retryable_statuses")]
+    pub retryable_statuses: Vec<u16>,
+}
+
+impl RetryConfig {
+    fn default_max_attempts() -> u32 {
+        1
+    }
+
+    fn default_base_delay_ms() -> u64 {
+        200
+    }
+
+    fn default_retryable_statuses() -> Vec<u16> {
+        vec![429, 502, 503, 504]
+    }
+}
+
+impl Default for RetryConfig {
+    fn default() -> Self {
+        Self {
+            max_attempts: Self::default_max_attempts(),
+            base_delay_ms: Self::default_base_delay_ms(),
+            retryable_statuses: Self::default_retryable_statuses(),
+        }
+    }
+}
+
+/// 按请求里的 timeout/connect_timeout/max_redirects 配置客户端
+fn configure_client(mut builder: reqwest::ClientBuilder, request: &ApiRequest) -> reqwest::ClientBuilder {
+    if let Some(ms) = request.timeout_ms {
+        builder = builder.timeout(std::time::Duration::from_millis(ms));
+    }
+    if let Some(ms) = request.connect_timeout_ms {
+        builder = builder.connect_timeout(std::time::Duration::from_millis(ms));
+    }
+    builder = match request.max_redirects {
+        Some(0) => builder.redirect(reqwest::redirect::Policy::none()),
+        Some(n) => builder.redirect(reqwest::redirect::Policy::limited(n)),
+        None => builder,
+    };
+    builder
+}
+
+/// 指数退避 + 抖动，`attempt` 从 1 开始
+fn backoff_delay(attempt: u32, base_delay_ms: u64) -> std::time::Duration {
+    let exp_ms = base_delay_ms.saturating_mul(1u64 << attempt.saturating_sub(1).min(10));
+    let jitter_ms = rand::thread_rng().gen_range(0..=(exp_ms / 2 + 1));
+    std::time::Duration::from_millis(exp_ms + jitter_ms)
+}
+
+fn retry_after_delay(response: &reqwest::Response) -> Option<std::time::Duration> {
+    response
+        .headers()
+        .get(reqwest::header::RETRY_AFTER)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|s| s.parse::<u64>().ok())
+        .map(std::time::Duration::from_secs)
+}
+
+/// 以 `template` 为蓝本反复发送请求，直到成功、遇到不可重试的错误，或用尽
+/// `retry.max_attempts` 次尝试。返回实际发出的响应和总尝试次数。
+async fn send_with_retry(
+    template: &reqwest::RequestBuilder,
+    retry: &RetryConfig,
+    log_prefix: &str,
+) -> Result<(reqwest::Response, u32), String> {
+    let mut attempt: u32 = 0;
+    loop {
+        attempt += 1;
+        let builder = template
+            .try_clone()
+            .ok_or_else(|| "Request body does not support retries".to_string())?;
+
+        match builder.send().await {
+            Ok(response) => {
+                let status = response.status().as_u16();
+                if attempt < retry.max_attempts && retry.retryable_statuses.contains(&status) {
+                    let delay = retry_after_delay(&response)
+                        .unwrap_or_else(|| backoff_delay(attempt, retry.base_delay_ms));
+                    log::warn!("⏳ {} Retryable status {} on attempt {}/{}, retrying in {:?}",
+                        log_prefix, status, attempt, retry.max_attempts, delay);
+                    tokio::time::sleep(delay).await;
+                    continue;
+                }
+                return Ok((response, attempt));
+            }
+            Err(err) => {
+                if attempt < retry.max_attempts && (err.is_timeout() || err.is_connect()) {
+                    let delay = backoff_delay(attempt, retry.base_delay_ms);
+                    log::warn!("⏳ {} Connection error on attempt {}/{}: {}, retrying in {:?}",
+                        log_prefix, attempt, retry.max_attempts, err, delay);
+                    tokio::time::sleep(delay).await;
+                    continue;
+                }
+                return Err(format!("HTTP request failed after {} attempt(s): {}", attempt, err));
+            }
+        }
+    }
+}
+
+/// `api_proxy` 读取响应体的方式
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+#[serde(tag = "mode", rename_all = "snake_case")]
+pub enum ResponseMode {
+    /// 按 UTF-8 文本读取（原有行为）
+    #[default]
+    Text,
+    /// 按字节读取后做 base64 编码，用于二进制响应（图片、zip 等）
+    Base64,
+    /// 边读边写到本地文件，避免大文件被整体缓冲进内存。
+    /// `path` 是相对路径，会被限制在 app 的数据目录下（见 `resolve_download_path`），
+    /// 不能用绝对路径或 `..` 逃逸出去写到任意位置。
+    DownloadToFile { path: String },
 }
 
+/// 把 `DownloadToFile.path` 限定在 app 数据目录内，拒绝绝对路径和试图用 `..`
+/// 逃逸出去的相对路径，避免调用方（包括 webview 里的任意前端代码）借这个命令
+/// 覆盖任意文件。和 `api_proxy` 本身"webview 可以请求任意 URL"的信任边界一样，
+/// 这里信任的是 URL/请求内容，但落盘位置必须收紧到 app 自己的沙盒目录。
+fn resolve_download_path(app: &AppHandle, path: &str) -> Result<PathBuf, String> {
+    let requested = Path::new(path);
+    if requested.is_absolute() {
+        return Err(format!(
+            "DownloadToFile path must be relative to the app data directory, got absolute path: {}",
+            path
+        ));
+    }
+
+    let base = app
+        .path()
+        .app_data_dir()
+        .map_err(|e| format!("Failed to resolve app data dir: {}", e))?;
+
+    let joined = base.join(requested);
+    let normalized = normalize_path(&joined);
+    if !normalized.starts_with(&base) {
+        return Err(format!(
+            "DownloadToFile path escapes the app data directory: {}",
+            path
+        ));
+    }
+
+    Ok(normalized)
+}
+
+/// 在不触碰文件系统的前提下折叠 `.`/`..`，用于在落盘前判断路径是否逃出了 base 目录
+/// （这时候文件通常还不存在，没法用 `fs::canonicalize` 来做同样的事）。
+fn normalize_path(path: &Path) -> PathBuf {
+    let mut result = PathBuf::new();
+    for component in path.components() {
+        match component {
+            std::path::Component::ParentDir => {
+                result.pop();
+            }
+            std::path::Component::CurDir => {}
+            other => result.push(other.as_os_str()),
+        }
+    }
+    result
+}
+
+/// `DownloadToFile` 模式下的下载进度，随每个 chunk 通过 `download-progress` 事件发出
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct DownloadProgress {
+    pub request_id: String,
+    pub bytes_written: u64,
+    pub content_length: Option<u64>,
+}
+
+/// 一个已解析的 SSE 事件，字段对应 `event:`/`id:`/`data:`，
+/// 由 `eventsource-stream` 按规范拼接多行 `data:` 并在空行处派发。
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct StreamChunk {
     pub stream_id: String,
+    pub event: Option<String>,
+    pub id: Option<String>,
     pub data: String,
 }
 
@@ -41,36 +280,77 @@ pub struct ApiError {
     pub status: Option<u16>,
 }
 
+/// 将响应体以流式方式写入本地文件，边下载边写盘，并通过 `download-progress`
+/// 事件汇报进度，避免大文件被整体缓冲进内存。落盘位置经 `resolve_download_path`
+/// 收紧到 app 数据目录下，调用方传入的 `path` 不能逃逸出去。
+async fn download_to_file(
+    app: &AppHandle,
+    request_id: &str,
+    response: reqwest::Response,
+    path: &str,
+    content_length: Option<u64>,
+) -> Result<u64, String> {
+    let resolved_path = resolve_download_path(app, path)?;
+
+    if let Some(parent) = resolved_path.parent() {
+        tokio::fs::create_dir_all(parent)
+            .await
+            .map_err(|e| format!("Failed to create directory {}: {}", parent.display(), e))?;
+    }
+
+    let mut file = tokio::fs::File::create(&resolved_path)
+        .await
+        .map_err(|e| format!("Failed to create file {}: {}", resolved_path.display(), e))?;
+
+    let mut stream = response.bytes_stream();
+    let mut bytes_written: u64 = 0;
+
+    while let Some(chunk_result) = stream.next().await {
+        let chunk = chunk_result.map_err(|e| format!("Stream read error: {}", e))?;
+        file.write_all(&chunk)
+            .await
+            .map_err(|e| format!("Failed to write to {}: {}", resolved_path.display(), e))?;
+        bytes_written += chunk.len() as u64;
+
+        let _ = app.emit("download-progress", DownloadProgress {
+            request_id: request_id.to_string(),
+            bytes_written,
+            content_length,
+        });
+    }
+
+    file.flush()
+        .await
+        .map_err(|e| format!("Failed to flush {}: {}", resolved_path.display(), e))?;
+    Ok(bytes_written)
+}
+
 #[command]
-pub async fn api_proxy(request: ApiRequest) -> Result<ApiResponse, ApiError> {
+pub async fn api_proxy(app: AppHandle, request: ApiRequest) -> Result<ApiResponse, ApiError> {
     let start_time = std::time::Instant::now();
     let request_id = uuid::Uuid::new_v4().to_string()[..8].to_string();
     
     log::info!("🚀 [Tauri-API-{}] START: {} {}", request_id, request.method, request.url);
 
-    // 创建 HTTP 客户端
-    let client = reqwest::Client::builder()
-        .user_agent("Tauri-API-Proxy/1.0")
-        .build()
-        .map_err(|e| ApiError {
-            message: format!("Failed to create HTTP client: {}", e),
-            status: None,
-        })?;
+    // 创建 HTTP 客户端；gzip/brotli/deflate 由 reqwest 自己透明解压（包括
+    // DownloadToFile 分支用到的 bytes_stream()），这里不需要手动解码
+    let client = configure_client(
+        reqwest::Client::builder()
+            .user_agent("Tauri-API-Proxy/1.0")
+            .gzip(true)
+            .brotli(true)
+            .deflate(true),
+        &request,
+    )
+    .build()
+    .map_err(|e| ApiError {
+        message: format!("Failed to create HTTP client: {}", e),
+        status: None,
+    })?;
 
     // 构建请求
-    let mut req_builder = match request.method.to_uppercase().as_str() {
-        "GET" => client.get(&request.url),
-        "POST" => client.post(&request.url),
-        "PUT" => client.put(&request.url),
-        "DELETE" => client.delete(&request.url),
-        "PATCH" => client.patch(&request.url),
-        "HEAD" => client.head(&request.url),
-        "OPTIONS" => client.request(reqwest::Method::OPTIONS, &request.url),
-        _ => return Err(ApiError {
-            message: format!("Unsupported HTTP method: {}", request.method),
-            status: None,
-        }),
-    };
+    let mut req_builder = method_request_builder(&client, &request.method, &request.url)
+        .map_err(|message| ApiError { message, status: None })?;
 
     // 添加 Tauri 标识头
     req_builder = req_builder.header("X-Tauri-Proxy", "true");
@@ -90,14 +370,16 @@ pub async fn api_proxy(request: ApiRequest) -> Result<ApiResponse, ApiError> {
         req_builder = req_builder.body(body.clone());
     }
 
-    // 发送请求
-    match req_builder.send().await {
-        Ok(response) => {
+    // 发送请求，按 retry 配置在可重试的状态码/连接错误上做指数退避重试
+    let retry_cfg = request.retry.clone().unwrap_or_default();
+    let log_prefix = format!("[Tauri-API-{}]", request_id);
+    match send_with_retry(&req_builder, &retry_cfg, &log_prefix).await {
+        Ok((response, attempts)) => {
             let status = response.status().as_u16();
             let elapsed = start_time.elapsed();
-            
-            log::info!("📥 [Tauri-API-{}] Response: {} in {:?}", request_id, status, elapsed);
-            
+
+            log::info!("📥 [Tauri-API-{}] Response: {} in {:?} ({} attempt(s))", request_id, status, elapsed, attempts);
+
             // 获取响应头
             let mut headers = HashMap::new();
             for (key, value) in response.headers() {
@@ -106,27 +388,62 @@ pub async fn api_proxy(request: ApiRequest) -> Result<ApiResponse, ApiError> {
                 }
             }
 
+            // 响应体已经（或即将）被透明解压，Content-Encoding 头不再准确，不转发给前端
+            strip_content_encoding(&mut headers);
+
             // 添加 Tauri 代理标识头
             headers.insert("X-Tauri-Proxied".to_string(), "true".to_string());
             headers.insert("X-Tauri-Request-ID".to_string(), request_id.clone());
             headers.insert("X-Tauri-Elapsed-Ms".to_string(), elapsed.as_millis().to_string());
+            headers.insert("X-Tauri-Attempts".to_string(), attempts.to_string());
 
-            // 获取响应体
-            match response.text().await {
-                Ok(body) => {
-                    log::info!("✅ [Tauri-API-{}] SUCCESS: {} bytes received", request_id, body.len());
-                    Ok(ApiResponse {
-                        status,
-                        headers,
-                        body,
-                    })
-                }
-                Err(err) => {
-                    log::error!("❌ [Tauri-API-{}] Failed to read response body: {}", request_id, err);
-                    Err(ApiError {
-                        message: format!("Failed to read response body: {}", err),
-                        status: Some(status),
-                    })
+            // 获取响应体，读取方式取决于 response_mode
+            match request.response_mode {
+                ResponseMode::Text => match response.text().await {
+                    Ok(body) => {
+                        log::info!("✅ [Tauri-API-{}] SUCCESS: {} bytes received", request_id, body.len());
+                        Ok(ApiResponse { status, headers, body })
+                    }
+                    Err(err) => {
+                        log::error!("❌ [Tauri-API-{}] Failed to read response body: {}", request_id, err);
+                        Err(ApiError {
+                            message: format!("Failed to read response body: {}", err),
+                            status: Some(status),
+                        })
+                    }
+                },
+                ResponseMode::Base64 => match response.bytes().await {
+                    Ok(bytes) => {
+                        log::info!("✅ [Tauri-API-{}] SUCCESS: {} bytes received (base64)", request_id, bytes.len());
+                        Ok(ApiResponse {
+                            status,
+                            headers,
+                            body: base64::engine::general_purpose::STANDARD.encode(&bytes),
+                        })
+                    }
+                    Err(err) => {
+                        log::error!("❌ [Tauri-API-{}] Failed to read response body: {}", request_id, err);
+                        Err(ApiError {
+                            message: format!("Failed to read response body: {}", err),
+                            status: Some(status),
+                        })
+                    }
+                },
+                ResponseMode::DownloadToFile { path } => {
+                    let content_length = response.content_length();
+                    match download_to_file(&app, &request_id, response, &path, content_length).await {
+                        Ok(bytes_written) => {
+                            log::info!("✅ [Tauri-API-{}] SUCCESS: {} bytes written to {}", request_id, bytes_written, path);
+                            Ok(ApiResponse { status, headers, body: path })
+                        }
+                        Err(err) => {
+                            log::error!("❌ [Tauri-API-{}] Download failed: {}", request_id, err);
+                            Err(ApiError {
+                                message: format!("Download failed: {}", err),
+                                status: Some(status),
+                            })
+                        }
+                    }
                 }
             }
         }
@@ -143,6 +460,7 @@ pub async fn api_proxy(request: ApiRequest) -> Result<ApiResponse, ApiError> {
 
 #[command]
 pub async fn simple_api_proxy(
+    app: AppHandle,
     url: String,
     method: String,
     headers: Option<HashMap<String, String>>,
@@ -153,9 +471,14 @@ pub async fn simple_api_proxy(
         method,
         headers,
         body,
+        response_mode: ResponseMode::Text,
+        timeout_ms: None,
+        connect_timeout_ms: None,
+        max_redirects: None,
+        retry: None,
     };
 
-    match api_proxy(request).await {
+    match api_proxy(app, request).await {
         Ok(response) => Ok(response.body),
         Err(error) => Err(error.message),
     }
@@ -173,27 +496,24 @@ pub async fn api_stream_proxy(
     
     log::info!("🌊 [Tauri-Stream-{}] START: {} {}", request_id, request.method, request.url);
 
-    // 创建 HTTP 客户端
-    let client = reqwest::Client::builder()
-        .user_agent("Tauri-Stream-Proxy/1.0")
-        .build()
-        .map_err(|e| ApiError {
-            message: format!("Failed to create HTTP client: {}", e),
-            status: None,
-        })?;
+    // 创建 HTTP 客户端；gzip/brotli/deflate 由 reqwest 在 bytes_stream() 之前透明解压
+    let client = configure_client(
+        reqwest::Client::builder()
+            .user_agent("Tauri-Stream-Proxy/1.0")
+            .gzip(true)
+            .brotli(true)
+            .deflate(true),
+        &request,
+    )
+    .build()
+    .map_err(|e| ApiError {
+        message: format!("Failed to create HTTP client: {}", e),
+        status: None,
+    })?;
 
     // 构建请求
-    let mut req_builder = match request.method.to_uppercase().as_str() {
-        "GET" => client.get(&request.url),
-        "POST" => client.post(&request.url),
-        "PUT" => client.put(&request.url),
-        "DELETE" => client.delete(&request.url),
-        "PATCH" => client.patch(&request.url),
-        _ => return Err(ApiError {
-            message: format!("Unsupported HTTP method: {}", request.method),
-            status: None,
-        }),
-    };
+    let mut req_builder = method_request_builder(&client, &request.method, &request.url)
+        .map_err(|message| ApiError { message, status: None })?;
 
     // 添加请求头
     if let Some(headers) = &request.headers {
@@ -209,16 +529,25 @@ pub async fn api_stream_proxy(
 
     let stream_id_clone = stream_id.clone();
     let app_clone = app.clone();
-    
-    // 在后台任务中处理流式响应
+    let retry_cfg = request.retry.clone().unwrap_or_default();
+
+    // 注册取消句柄，使 cancel_stream 可以随时中止这个任务
+    let cancel_token = CancellationToken::new();
+    STREAM_REGISTRY.lock().unwrap().insert(stream_id.clone(), cancel_token.clone());
+
+    // 在后台任务中处理流式响应。重试只发生在建立连接阶段（下面这次 send），
+    // 一旦开始读取事件就不再重试，避免向前端重复投递已经发出的 SSE 事件
     tauri::async_runtime::spawn(async move {
-        match req_builder.send().await {
-            Ok(response) => {
+        let log_prefix = format!("[Tauri-Stream-{}]", request_id);
+        match send_with_retry(&req_builder, &retry_cfg, &log_prefix).await {
+            Ok((response, attempts)) => {
                 let status = response.status().as_u16();
-                
+                log::info!("📥 [Tauri-Stream-{}] Response status: {} ({} attempt(s))", request_id, status, attempts);
+
                 if status >= 400 {
                     let error_msg = format!("HTTP Error: {}", status);
                     log::error!("❌ [Tauri-Stream-{}] {}", request_id, error_msg);
+                    unregister_stream(&stream_id_clone);
                     let _ = app_clone.emit("stream-error", StreamError {
                         stream_id: stream_id_clone.clone(),
                         error: error_msg,
@@ -226,176 +555,68 @@ pub async fn api_stream_proxy(
                     return;
                 }
 
-                log::info!("📥 [Tauri-Stream-{}] Response status: {}", request_id, status);
-
-                // 流式读取响应体
-                let mut stream = response.bytes_stream();
-                let mut buffer = String::new();
+                // 用符合 SSE 规范的解码器逐事件读取响应体（已经被 reqwest 透明解压），
+                // 由它负责多行 data: 拼接、注释跳过和空行派发边界
+                let mut stream = response.bytes_stream().eventsource();
                 let mut chunk_count = 0;
-                let mut pending_data_prefix = false; // 标记是否有待处理的 data: 前缀
-
-                while let Some(chunk_result) = stream.next().await {
-                    match chunk_result {
-                        Ok(chunk) => {
-                            chunk_count += 1;
-                            
-                            // 将字节转换为字符串
-                            match String::from_utf8(chunk.to_vec()) {
-                                Ok(text) => {
-                                    buffer.push_str(&text);
-                                    
-                                    // 按行分割处理 SSE 数据
-                                    let lines_vec: Vec<String> = buffer.lines().map(|s| s.to_string()).collect();
-                                    
-                                    // 如果最后没有换行符，保留最后一行到buffer
-                                    let remaining = if !buffer.ends_with('\n') && !lines_vec.is_empty() {
-                                        lines_vec.last().unwrap().clone()
-                                    } else {
-                                        String::new()
-                                    };
-                                    
-                                    let lines_to_process = if !remaining.is_empty() {
-                                        &lines_vec[..lines_vec.len() - 1]
-                                    } else {
-                                        &lines_vec[..]
-                                    };
-                                    
-                                    buffer = remaining;
-                                    
-                                    // 处理完整的行，合并多行 SSE 格式
-                                    let mut i = 0;
-                                    while i < lines_to_process.len() {
-                                        let line = &lines_to_process[i];
-                                        let trimmed = line.trim();
-                                        
-                                        // 跳过空行和注释
-                                        if trimmed.is_empty() || trimmed.starts_with(':') {
-                                            i += 1;
-                                            continue;
-                                        }
-                                        
-                                        // 检测到 data: 前缀
-                                        if trimmed == "data:" || trimmed.starts_with("data:") {
-                                            let formatted_line = if trimmed == "data:" {
-                                                // data: 单独一行，需要合并下一行的 JSON 内容
-                                                if i + 1 < lines_to_process.len() {
-                                                    let next_line = lines_to_process[i + 1].trim();
-                                                    if next_line.starts_with('{') || next_line.starts_with('[') {
-                                                        i += 1; // 跳过下一行，因为已经合并了
-                                                        format!("data: {}", next_line)
-                                                    } else {
-                                                        format!("data: {}", next_line)
-                                                    }
-                                                } else {
-                                                    // 没有下一行了，设置标记等待
-                                                    pending_data_prefix = true;
-                                                    i += 1;
-                                                    continue;
-                                                }
-                                            } else if let Some(json_part) = trimmed.strip_prefix("data:") {
-                                                // data: 和 JSON 在同一行
-                                                let json_trimmed = json_part.trim();
-                                                if json_trimmed.is_empty() {
-                                                    // data: 后面是空的，等待下一行
-                                                    pending_data_prefix = true;
-                                                    i += 1;
-                                                    continue;
-                                                } else {
-                                                    format!("data: {}", json_trimmed)
-                                                }
-                                            } else {
-                                                line.clone()
-                                            };
-                                            
-                                            log::debug!("📤 [Tauri-Stream-{}] Sending: {}", 
-                                                request_id, 
-                                                if formatted_line.len() > 100 { 
-                                                    format!("{}...", &formatted_line[..100]) 
-                                                } else { 
-                                                    formatted_line.clone() 
-                                                });
-                                            
-                                            // 发送数据块事件（SSE 格式，包含换行符）
-                                            if let Err(e) = app_clone.emit("stream-chunk", StreamChunk {
-                                                stream_id: stream_id_clone.clone(),
-                                                data: format!("{}\n", formatted_line),
-                                            }) {
-                                                log::error!("❌ [Tauri-Stream-{}] Failed to emit chunk: {}", request_id, e);
-                                                break;
-                                            }
-                                        } else if pending_data_prefix && (trimmed.starts_with('{') || trimmed.starts_with('[')) {
-                                            // 这是 data: 后面的 JSON 内容
-                                            let formatted_line = format!("data: {}", trimmed);
-                                            pending_data_prefix = false;
-                                            
-                                            log::debug!("📤 [Tauri-Stream-{}] Sending (merged): {}", 
-                                                request_id, 
-                                                if formatted_line.len() > 100 { 
-                                                    format!("{}...", &formatted_line[..100]) 
-                                                } else { 
-                                                    formatted_line.clone() 
-                                                });
-                                            
-                                            if let Err(e) = app_clone.emit("stream-chunk", StreamChunk {
-                                                stream_id: stream_id_clone.clone(),
-                                                data: format!("{}\n", formatted_line),
-                                            }) {
-                                                log::error!("❌ [Tauri-Stream-{}] Failed to emit chunk: {}", request_id, e);
-                                                break;
-                                            }
-                                        }
-                                        
-                                        i += 1;
+                let mut cancelled = false;
+
+                loop {
+                    tokio::select! {
+                        _ = cancel_token.cancelled() => {
+                            log::info!("🛑 [Tauri-Stream-{}] Cancelled after {} events", request_id, chunk_count);
+                            cancelled = true;
+                            break;
+                        }
+                        event_result = stream.next() => {
+                            let Some(event_result) = event_result else { break };
+                            match event_result {
+                                Ok(event) => {
+                                    chunk_count += 1;
+
+                                    log::debug!("📤 [Tauri-Stream-{}] Sending event={:?} id={:?} data.len={}",
+                                        request_id, event.event, event.id, event.data.len());
+
+                                    if let Err(e) = app_clone.emit("stream-chunk", StreamChunk {
+                                        stream_id: stream_id_clone.clone(),
+                                        event: if event.event.is_empty() { None } else { Some(event.event) },
+                                        id: if event.id.is_empty() { None } else { Some(event.id) },
+                                        data: event.data,
+                                    }) {
+                                        log::error!("❌ [Tauri-Stream-{}] Failed to emit chunk: {}", request_id, e);
+                                        break;
                                     }
                                 }
                                 Err(e) => {
-                                    log::error!("❌ [Tauri-Stream-{}] UTF-8 decode error: {}", request_id, e);
+                                    log::error!("❌ [Tauri-Stream-{}] SSE decode error: {}", request_id, e);
+                                    unregister_stream(&stream_id_clone);
                                     let _ = app_clone.emit("stream-error", StreamError {
                                         stream_id: stream_id_clone.clone(),
-                                        error: format!("UTF-8 decode error: {}", e),
+                                        error: format!("SSE decode error: {}", e),
                                     });
                                     return;
                                 }
                             }
                         }
-                        Err(e) => {
-                            log::error!("❌ [Tauri-Stream-{}] Stream read error: {}", request_id, e);
-                            let _ = app_clone.emit("stream-error", StreamError {
-                                stream_id: stream_id_clone.clone(),
-                                error: format!("Stream read error: {}", e),
-                            });
-                            return;
-                        }
                     }
                 }
 
-                // 处理剩余的 buffer
-                if !buffer.trim().is_empty() {
-                    let trimmed = buffer.trim();
-                    // 确保数据行包含 data: 前缀
-                    let formatted = if trimmed.starts_with("data:") {
-                        buffer.clone()
-                    } else if trimmed.starts_with('{') || trimmed.starts_with('[') {
-                        format!("data: {}", trimmed)
-                    } else {
-                        buffer.clone()
-                    };
-                    
-                    let _ = app_clone.emit("stream-chunk", StreamChunk {
-                        stream_id: stream_id_clone.clone(),
-                        data: format!("{}\n", formatted),
-                    });
+                unregister_stream(&stream_id_clone);
+
+                if cancelled {
+                    log::info!("✅ [Tauri-Stream-{}] STOPPED: {} events received before cancel", request_id, chunk_count);
+                } else {
+                    log::info!("✅ [Tauri-Stream-{}] COMPLETED: {} events received", request_id, chunk_count);
                 }
 
-                log::info!("✅ [Tauri-Stream-{}] COMPLETED: {} chunks received", request_id, chunk_count);
-                
-                // 发送流结束事件
+                // 发送流结束事件（取消和正常完成都走这里，行为对前端一致）
                 let _ = app_clone.emit("stream-end", StreamEnd {
                     stream_id: stream_id_clone,
                 });
             }
             Err(err) => {
                 log::error!("❌ [Tauri-Stream-{}] HTTP request failed: {}", request_id, err);
+                unregister_stream(&stream_id_clone);
                 let _ = app_clone.emit("stream-error", StreamError {
                     stream_id: stream_id_clone,
                     error: format!("HTTP request failed: {}", err),
@@ -406,3 +627,122 @@ pub async fn api_stream_proxy(
 
     Ok(stream_id)
 }
+
+/// 取消一个正在进行的流式请求；对应的后台任务会在读取下一个事件前停止，
+/// 发出最终的 stream-end 并断开 HTTP 连接。
+#[command]
+pub fn cancel_stream(stream_id: String) -> Result<(), ApiError> {
+    let registry = STREAM_REGISTRY.lock().unwrap();
+    match registry.get(&stream_id) {
+        Some(token) => {
+            token.cancel();
+            Ok(())
+        }
+        None => Err(ApiError {
+            message: format!("Stream not found or already finished: {}", stream_id),
+            status: None,
+        }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use std::net::TcpListener;
+    use std::thread;
+
+    /// 起一个只接受一次连接的本地 HTTP 服务，把 `body` gzip 压缩后切成多个
+    /// chunk 用 `Transfer-Encoding: chunked` 发出去，模拟一个持续产出数据、
+    /// 带 `Content-Encoding: gzip` 的上游响应。
+    /// body 的最后一块要等 `release_tail` 收到信号（最多等 10 秒）才发出。
+    fn spawn_gzip_fixture_server(
+        body: &'static [u8],
+        release_tail: std::sync::mpsc::Receiver<()>,
+    ) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").expect("bind fixture server");
+        let addr = listener.local_addr().expect("fixture server local addr");
+
+        thread::spawn(move || {
+            let (mut stream, _) = listener.accept().expect("accept fixture connection");
+
+            let mut encoder =
+                flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+            encoder.write_all(body).expect("gzip-compress fixture body");
+            let compressed = encoder.finish().expect("finish gzip stream");
+
+            stream
+                .write_all(b"HTTP/1.1 200 OK\r\nContent-Encoding: gzip\r\nTransfer-Encoding: chunked\r\n\r\n")
+                .expect("write response head");
+
+            // 故意切成多个小 chunk 发送，用来验证下游是逐块拿到解压后的数据，
+            // 而不是等上游关闭连接、拿到完整 body 之后才能处理。
+            let mut write_piece = |piece: &[u8]| {
+                write!(stream, "{:x}\r\n", piece.len()).expect("write chunk size");
+                stream.write_all(piece).expect("write chunk body");
+                stream.write_all(b"\r\n").expect("write chunk trailer");
+                stream.flush().expect("flush chunk");
+            };
+            let pieces: Vec<&[u8]> = compressed.chunks(8).collect();
+            let (last, rest) = pieces.split_last().expect("non-empty gzip stream");
+            for &piece in rest {
+                write_piece(piece);
+            }
+            // 最后一块（gzip trailer 的一部分）扣住，等客户端拿到解压数据后再发
+            let _ = release_tail.recv_timeout(std::time::Duration::from_secs(10));
+            write_piece(*last);
+            stream.write_all(b"0\r\n\r\n").expect("write final chunk");
+        });
+
+        format!("http://{}/", addr)
+    }
+
+    /// 对应 `api_stream_proxy`/`download_to_file` 共用的客户端配置：
+    /// `gzip(true)` 打开后，reqwest 在 `bytes_stream()` 之前就已经透明解压，
+    /// 这里用一个真正分块发送的 gzip 压缩 fixture 验证这一点，而不是只信注释。
+    #[tokio::test]
+    async fn gzip_stream_decodes_incrementally() {
+        let body: &'static [u8] =
+            Box::leak(b"hello from a gzip-compressed streaming response, ".repeat(200).into_boxed_slice());
+        let (release_tx, release_rx) = std::sync::mpsc::channel();
+        let url = spawn_gzip_fixture_server(body, release_rx);
+
+        let client = reqwest::Client::builder()
+            .gzip(true)
+            .build()
+            .expect("build gzip-enabled client");
+
+        let response = method_request_builder(&client, "GET", &url)
+            .expect("build request")
+            .send()
+            .await
+            .expect("send request");
+
+        // reqwest 解压后会把 Content-Encoding 从响应头里摘掉
+        assert!(response.headers().get("content-encoding").is_none());
+
+        let mut stream = response.bytes_stream();
+        // fixture 在这里拿到第一块解压数据之前不会发出 body 的最后一块，
+        // 所以能在超时内读到数据，就说明解压不需要等完整 body
+        let first = tokio::time::timeout(std::time::Duration::from_secs(5), stream.next())
+            .await
+            .expect("decoded data should arrive before the upstream body is complete")
+            .expect("stream ended before yielding any data")
+            .expect("read decoded chunk");
+        release_tx.send(()).expect("release fixture tail");
+
+        let mut chunk_count = 1;
+        let mut decoded = first.to_vec();
+        while let Some(chunk) = stream.next().await {
+            let chunk = chunk.expect("read decoded chunk");
+            chunk_count += 1;
+            decoded.extend_from_slice(&chunk);
+        }
+
+        assert!(
+            chunk_count > 1,
+            "expected the fixture body to arrive in more than one chunk"
+        );
+        assert_eq!(decoded, body);
+    }
+}
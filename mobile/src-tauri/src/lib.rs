@@ -1,15 +1,19 @@
 mod api_proxy;
 mod permissions;
+mod protocol;
 
-use api_proxy::{api_proxy, simple_api_proxy};
+use api_proxy::{api_proxy, api_stream_proxy, cancel_stream, simple_api_proxy};
 use permissions::{check_microphone_permission, request_microphone_permission};
 
 #[cfg_attr(mobile, tauri::mobile_entry_point)]
 pub fn run() {
-  tauri::Builder::default()
+  let builder = protocol::register(tauri::Builder::default());
+  builder
     .invoke_handler(tauri::generate_handler![
       api_proxy,
       simple_api_proxy,
+      api_stream_proxy,
+      cancel_stream,
       check_microphone_permission,
       request_microphone_permission
     ])
@@ -0,0 +1,136 @@
+use crate::api_proxy::{method_request_builder, strip_content_encoding};
+use futures_util::StreamExt;
+use std::collections::HashMap;
+use tauri::http::{HeaderName, HeaderValue, Request, Response, StatusCode};
+use tauri::{Runtime, UriSchemeContext, UriSchemeResponder};
+
+/// 前端通过 `fetch("bklite://<host>/<path>")` 访问的自定义协议名。
+/// 注册后请求会走和 `api_proxy` 相同的 reqwest 管线，不再需要 invoke + 事件组装。
+///
+/// 范围限制：这个协议只支持一次性返回的响应。Tauri 的
+/// `UriSchemeResponder::respond` 只接受一个完整缓冲好的 `http::Response<Vec<u8>>`，
+/// 没有逐块往 webview 推送数据的方式，所以这里没法做到真正的流式转发。
+/// `text/event-stream` 响应（聊天补全这类长连接 SSE）必须继续走
+/// `api_stream_proxy` + `stream-chunk`/`stream-end` 事件这条路径——
+/// `forward()` 会直接拒绝 Content-Type 为 `text/event-stream` 的响应，
+/// 避免调用方以为 `fetch("bklite://...")` 能拿到增量数据却只会在连接关闭后才 resolve。
+pub const SCHEME: &str = "bklite";
+
+/// `bklite://api.example.com/v1/foo?x=1` -> `https://api.example.com/v1/foo?x=1`
+fn upstream_url(request: &Request<Vec<u8>>) -> Result<String, String> {
+    let uri = request.uri();
+    let host = uri.host().ok_or_else(|| format!("Missing host in {}", uri))?;
+    let path_and_query = uri.path_and_query().map(|p| p.as_str()).unwrap_or("/");
+    Ok(format!("https://{}{}", host, path_and_query))
+}
+
+/// 注册在 `run()` 里调用；每个请求都在后台任务里异步转发，不阻塞 webview。
+///
+/// 只适用于一次性返回的响应——`text/event-stream` 会被 [`forward`] 拒绝，
+/// 需要流式数据的调用方请使用 `api_stream_proxy` 命令。
+pub fn register<R: Runtime>(
+    builder: tauri::Builder<R>,
+) -> tauri::Builder<R> {
+    builder.register_asynchronous_uri_scheme_protocol(SCHEME, move |_ctx: UriSchemeContext<R>, request, responder| {
+        tauri::async_runtime::spawn(async move {
+            respond(request, responder).await;
+        });
+    })
+}
+
+async fn respond(request: Request<Vec<u8>>, responder: UriSchemeResponder) {
+    match forward(request).await {
+        Ok(response) => responder.respond(response),
+        Err(message) => {
+            log::error!("❌ [bklite://] {}", message);
+            let body = message.into_bytes();
+            let response = Response::builder()
+                .status(StatusCode::BAD_GATEWAY)
+                .header("content-type", "text/plain; charset=utf-8")
+                .body(body)
+                .unwrap_or_else(|_| Response::new(Vec::new()));
+            responder.respond(response);
+        }
+    }
+}
+
+/// 上游响应的 Content-Type 是否为 `text/event-stream`。
+/// 命中时 `forward()` 会直接报错而不是把 SSE 流缓冲成一个永远等不到结束的 `Vec<u8>`。
+fn is_event_stream(headers: &HashMap<String, String>) -> bool {
+    headers
+        .get("content-type")
+        .is_some_and(|v| v.to_ascii_lowercase().contains("text/event-stream"))
+}
+
+/// 解析 `bklite://` 请求，转发给上游，再把状态码/响应头/响应体拼回一次性的 `http::Response`。
+/// gzip/brotli/deflate 由 reqwest 在 `bytes_stream()` 之前透明解压。
+///
+/// 注意：这里会把整个响应体读完再一次性 `respond()`，因为
+/// `UriSchemeResponder` 没有逐块推送的接口。长连接的 `text/event-stream`
+/// 响应（见 [`is_event_stream`]）会被拒绝，调用方应改用 `api_stream_proxy`。
+async fn forward(request: Request<Vec<u8>>) -> Result<Response<Vec<u8>>, String> {
+    let url = upstream_url(&request)?;
+    let method = request.method().as_str().to_string();
+    let body = request.body().clone();
+
+    let client = reqwest::Client::builder()
+        .user_agent("Tauri-Protocol-Proxy/1.0")
+        .gzip(true)
+        .brotli(true)
+        .deflate(true)
+        .build()
+        .map_err(|e| format!("Failed to create HTTP client: {}", e))?;
+
+    let mut req_builder = method_request_builder(&client, &method, &url)?;
+
+    for (name, value) in request.headers() {
+        if let Ok(value_str) = value.to_str() {
+            req_builder = req_builder.header(name.as_str(), value_str);
+        }
+    }
+
+    if !body.is_empty() {
+        req_builder = req_builder.body(body);
+    }
+
+    let upstream_response = req_builder
+        .send()
+        .await
+        .map_err(|e| format!("HTTP request failed: {}", e))?;
+
+    let status = upstream_response.status();
+
+    let mut headers = HashMap::new();
+    for (key, value) in upstream_response.headers() {
+        if let Ok(value_str) = value.to_str() {
+            headers.insert(key.to_string(), value_str.to_string());
+        }
+    }
+    strip_content_encoding(&mut headers);
+
+    if is_event_stream(&headers) {
+        return Err(
+            "bklite:// 不支持 text/event-stream 响应（无法增量推送数据，fetch() 只会在连接关闭后才 \
+             resolve）；SSE 接口请改用 api_stream_proxy + stream-chunk/stream-end 事件"
+                .to_string(),
+        );
+    }
+
+    let mut stream = upstream_response.bytes_stream();
+    let mut buffer = Vec::new();
+    while let Some(chunk) = stream.next().await {
+        let chunk = chunk.map_err(|e| format!("Stream read error: {}", e))?;
+        buffer.extend_from_slice(&chunk);
+    }
+
+    let mut builder = Response::builder().status(StatusCode::from_u16(status.as_u16()).unwrap_or(StatusCode::BAD_GATEWAY));
+    for (key, value) in &headers {
+        if let (Ok(name), Ok(value)) = (HeaderName::try_from(key.as_str()), HeaderValue::try_from(value.as_str())) {
+            builder = builder.header(name, value);
+        }
+    }
+
+    builder
+        .body(buffer)
+        .map_err(|e| format!("Failed to build response: {}", e))
+}